@@ -1,7 +1,11 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+pub mod migrations;
 #[cfg(test)]
 pub mod mock;
+#[cfg(feature = "offchain-notify")]
+pub mod notify;
+pub mod snapshot;
 #[cfg(test)]
 pub mod tests;
 
@@ -16,12 +20,53 @@ pub mod tests;
 	Available actions:
 
 	- submit_topic:
-			Here a signed user can submit a topic which is stored alongside it's hash.
-			A fee is required, to submit a proposal.
+			Here a user allowed by `SubmitOrigin` (any signed user by default) can submit a topic
+			which is stored alongside it's hash.
+			A fee is required, to submit a proposal, unless the user is in `FeeExempt`.
+			A topic can optionally carry up to `MaxTopicOptions` options to choose between.
 
 	-- vote_topic:
 			Allows you cote for a hash in the current era. A fee is charged for each vote,
 			and is a function of the square of the number of votes you have for that topic multiplied by the default weight.
+			Votes for a topic with options must pick one of them by index, and the era result then
+			records the winning option of the winning topic as well.
+			Only users allowed by `VoteOrigin` (any signed user by default) can vote.
+			Accounts in `FeeExempt` vote for free, but are still subject to the vote caps.
+			If the runtime sets `MaxVotersPerEra` and the era is full, first-time voters are placed on
+			a waitlist instead, and are admitted once capacity frees up. The waitlist holds at most
+			`MaxWaitlist` accounts, further voters are turned away.
+
+	- change_vote:
+			Move up to n of your votes in the current era from one active topic to another. The
+			moved votes are priced again as votes on the new topic, and the difference with the fees
+			already paid for them is charged or refunded.
+
+	- withdraw_votes:
+			Withdraw all of your votes in the current era, unreserving their fees. This frees your
+			voter slot, which is handed to the first account on the era's waitlist.
+
+	- cancel_era:
+			`AdminOrigin` only. Aborts the current era: all topic deposits and vote fees for the era are
+			unreserved, no winner is recorded and the era's topics go back to the next era queue.
+			The call takes upper bounds of the era's voter and topic counts, which it is weighed by.
+
+	- offchain notifications:
+			With the `offchain-notify` feature, the off-chain worker POSTs a JSON summary of every
+			finalized era to the HTTP endpoints configured by the node operator. See `notify.rs`.
+
+	- set_settlement_mode:
+			`AdminOrigin` only. Chooses how eras are settled from then on. In `Winner` mode the topic with
+			the most votes wins the era. In `BudgetAllocation` mode there is no winner, instead
+			`EraBudget` is paid out of the pallet pot to the providers of every topic which got at
+			least `AllocationThreshold` of the era's votes, in proportion to their votes.
+
+	- set_balance_weighting:
+			`AdminOrigin` only. Enables or disables balance weighting for the eras starting from then on.
+			In a weighted era each vote counts for the voter's free balance as of the era's first
+			block, its snapshot block, both towards its topic and its option. Funds bought during the
+			era don't weigh in, so they can't be voted with and sold again within the era. The
+			snapshot map is built lazily: `SnapshotAccountStore` has to wrap the `AccountStore` of the
+			balances pallet, so that an account's balance is recorded right before it first changes.
 
 	- get_current_topics:
 			Here a user can get all topics hashes which are available to be voted in the current era.
@@ -32,8 +77,23 @@ pub mod tests;
 	- get_topic_preimage:
 			Get the details of a topic given it's hash
 
+	- get_winning_option:
+			Returns the option which got the most votes within the winning topic of an era, if
+			that topic has options.
+
+	- get_vote_root:
+			Returns the Merkle root committed at the end of an era over its (voter, topic, count)
+			records. `vote_proof` builds a proof for a record while the era's votes are still in
+			storage, and `verify_vote` checks it against the root, even once the votes are gone.
+
+	- metrics:
+			Returns the running totals of topics submitted, votes cast, fees charged and eras
+			finalized, which are also available through their own getters.
+
 	- get_era_winners:
-			Returns a map of the era number, to the hash that won in that era
+			Returns a map of the era number, to the hash that won in that era. When an era is
+			settled, voters who backed the winning topic get `WinnerRebate` of the fees they paid
+			for it unreserved.
 
 */
 
@@ -45,21 +105,58 @@ pub mod pallet {
 	use super::*;
 	use frame_support::{
 		pallet_prelude::*,
-		sp_runtime::traits::{Hash, Zero},
-		traits::{Currency, LockableCurrency, ReservableCurrency},
+		sp_runtime::{
+			traits::{AccountIdConversion, Hash, SaturatedConversion, Saturating, Zero},
+			PerThing, Percent, Perquintill,
+		},
+		traits::{Contains, Currency, ExistenceRequirement, ReservableCurrency},
+		PalletId,
 	};
 	use frame_system::pallet_prelude::*;
 	use sp_std::{collections::btree_map::*, vec, vec::Vec, *};
 
-	type BalanceOf<T> =
+	pub(super) type BalanceOf<T> =
 		<<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
 
 	#[derive(Clone, Encode, Decode, RuntimeDebug, TypeInfo)]
 	pub struct Topic<AccountId, Balance, BlockNumber> {
-		data: Vec<u8>,
-		provider: AccountId,
-		deposit: Balance,
-		since: BlockNumber,
+		pub(super) data: Vec<u8>,
+		pub(super) provider: AccountId,
+		pub(super) deposit: Balance,
+		pub(super) since: BlockNumber,
+		pub(super) options: Vec<Vec<u8>>,
+	}
+
+	/// How the result of an era is settled.
+	#[derive(Clone, Copy, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+	pub enum SettlementMode {
+		/// The topic with the most votes wins the era.
+		Winner,
+		/// The era budget is split across the topics in proportion to their votes.
+		BudgetAllocation,
+	}
+
+	impl Default for SettlementMode {
+		fn default() -> Self {
+			SettlementMode::Winner
+		}
+	}
+
+	/// Running totals kept by the pallet since genesis.
+	#[derive(Clone, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+	pub struct Metrics<Balance> {
+		pub topics_submitted: u64,
+		pub votes_cast: u64,
+		pub fees_collected: Balance,
+		pub eras_finalized: u32,
+	}
+
+	/// A record of a single vote, kept so that the fee reserved for it can be traced back later.
+	#[derive(Clone, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+	pub struct VoteReceipt<Hash, Balance> {
+		pub topic: Hash,
+		pub option: Option<u32>,
+		pub fee: Balance,
 	}
 
 	#[pallet::config] // <-- Step 2. code block will replace this.
@@ -67,8 +164,7 @@ pub mod pallet {
 	pub trait Config: frame_system::Config {
 		/// Because this pallet emits events, it depends on the runtime's definition of an event.
 		type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
-		type Currency: ReservableCurrency<Self::AccountId>
-			+ LockableCurrency<Self::AccountId, Moment = Self::BlockNumber>;
+		type Currency: ReservableCurrency<Self::AccountId>;
 		// type Token: ReservableCurrency<Self::AccountId>;
 
 		/// The number of blocks between each era.
@@ -81,6 +177,50 @@ pub mod pallet {
 		// The max allowed number of votes a single user can make
 		#[pallet::constant]
 		type MaxVotes: Get<u16>;
+
+		/// The max number of distinct voters admitted in a single era. Once reached, further
+		/// first-time voters are placed on a waitlist. `None` means no cap.
+		#[pallet::constant]
+		type MaxVotersPerEra: Get<Option<u32>>;
+
+		/// The max number of first-time voters which can wait for a slot in a full era.
+		#[pallet::constant]
+		type MaxWaitlist: Get<u32>;
+
+		/// The share of the fees paid for the winning topic which is returned to its voters when
+		/// the era is settled.
+		#[pallet::constant]
+		type WinnerRebate: Get<Percent>;
+
+		/// The max number of options a single topic can carry.
+		#[pallet::constant]
+		type MaxTopicOptions: Get<u32>;
+
+		/// The pallet's id, used to derive the account of the pot which era budgets are paid from.
+		#[pallet::constant]
+		type PalletId: Get<PalletId>;
+
+		/// The amount split across topics at the end of an era in `BudgetAllocation` mode.
+		#[pallet::constant]
+		type EraBudget: Get<BalanceOf<Self>>;
+
+		/// The share of an era's votes a topic needs to get part of the budget in
+		/// `BudgetAllocation` mode.
+		#[pallet::constant]
+		type AllocationThreshold: Get<Percent>;
+
+		/// The origin which can submit topics, resolving to the account providing the topic.
+		type SubmitOrigin: EnsureOrigin<Self::Origin, Success = Self::AccountId>;
+
+		/// The origin which can vote, resolving to the voting account.
+		type VoteOrigin: EnsureOrigin<Self::Origin, Success = Self::AccountId>;
+
+		/// The origin which can cancel eras and change how they are settled.
+		type AdminOrigin: EnsureOrigin<Self::Origin>;
+
+		/// Accounts which can submit topics and vote without paying deposits or fees, e.g. council
+		/// members. They are still subject to `MaxVotes` and `MaxVotersPerEra`.
+		type FeeExempt: Contains<Self::AccountId>;
 	}
 
 	#[pallet::event]
@@ -88,7 +228,16 @@ pub mod pallet {
 	pub enum Event<T: Config> {
 		NewTopic { who: T::AccountId, topic_hash: T::Hash, deposit: BalanceOf<T> },
 		NewEra { era: T::BlockNumber },
-		NewVote { who: T::AccountId, topic_hash: T::Hash },
+		NewVote { who: T::AccountId, topic_hash: T::Hash, option: Option<u32> },
+		VoterWaitlisted { who: T::AccountId, era: T::BlockNumber },
+		VoterAdmitted { who: T::AccountId, era: T::BlockNumber },
+		VotesWithdrawn { who: T::AccountId, era: T::BlockNumber, refund: BalanceOf<T> },
+		FeeRebated { who: T::AccountId, era: T::BlockNumber, amount: BalanceOf<T> },
+		EraCancelled { era: T::BlockNumber },
+		SettlementModeSet { mode: SettlementMode },
+		BudgetAllocated { era: T::BlockNumber, topic_hash: T::Hash, amount: BalanceOf<T> },
+		BalanceWeightingSet { enabled: bool },
+		VotesChanged { who: T::AccountId, from_topic: T::Hash, to_topic: T::Hash, count: u32 },
 	}
 
 	#[pallet::error] // <-- Step 4. code block will replace this.
@@ -96,10 +245,32 @@ pub mod pallet {
 		DuplicateTopic,
 		InvalidTopicHash,
 		VoterReachedMaxVotes,
+		/// The era is full and the voter is still waiting for a free slot.
+		StillWaitlisted,
+		/// The era is full and so is its waitlist.
+		WaitlistFull,
+		/// The account has not been admitted as a voter in the current era.
+		NotAVoter,
+		/// The current era has been cancelled and no longer accepts votes.
+		EraIsCancelled,
+		/// The topic has more options than `MaxTopicOptions`.
+		TooManyOptions,
+		/// The option doesn't exist for the topic, or is missing for a topic which has options.
+		InvalidOption,
+		/// Votes can only be moved between two different topics.
+		SameTopic,
+		/// The voter has no votes on the topic to move them from.
+		NoVotesToMove,
+		/// The voter or topic count given is below the actual one.
+		InvalidWitness,
 	}
 
+	// Version 1 added the options of topics.
+	const STORAGE_VERSION: StorageVersion = StorageVersion::new(1);
+
 	#[pallet::pallet]
 	#[pallet::generate_store(pub(super) trait Store)]
+	#[pallet::storage_version(STORAGE_VERSION)]
 	pub struct Pallet<T>(_);
 
 	#[pallet::storage]
@@ -133,12 +304,127 @@ pub mod pallet {
 	pub(super) type Votes<T: Config> =
 		StorageMap<_, Blake2_128, T::BlockNumber, Vec<(T::Hash, T::AccountId)>, OptionQuery>;
 
+	#[pallet::storage]
+	#[pallet::unbounded]
+	#[pallet::getter(fn get_vote_receipts)]
+	// VoteReceipts holds the fee reserved for every vote an account made in an era.
+	pub(super) type VoteReceipts<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		T::BlockNumber,
+		Blake2_128Concat,
+		T::AccountId,
+		Vec<VoteReceipt<T::Hash, BalanceOf<T>>>,
+		ValueQuery,
+	>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn is_era_voter)]
+	// EraVoters holds the accounts which have been admitted to vote in an era.
+	pub(super) type EraVoters<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		T::BlockNumber,
+		Blake2_128Concat,
+		T::AccountId,
+		bool,
+		ValueQuery,
+	>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn get_voter_count)]
+	pub(super) type VoterCount<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::BlockNumber, u32, ValueQuery>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn get_waitlist)]
+	// Waitlist holds, in arrival order, the first-time voters which didn't fit in a full era.
+	pub(super) type Waitlist<T: Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		T::BlockNumber,
+		BoundedVec<T::AccountId, T::MaxWaitlist>,
+		ValueQuery,
+	>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn is_era_cancelled)]
+	pub(super) type CancelledEras<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::BlockNumber, bool, ValueQuery>;
+
 	#[pallet::storage]
 	#[pallet::unbounded]
 	#[pallet::getter(fn get_winners)]
 	pub(super) type Winners<T: Config> =
 		StorageMap<_, Blake2_128, T::BlockNumber, T::Hash, OptionQuery>;
 
+	#[pallet::storage]
+	#[pallet::unbounded]
+	#[pallet::getter(fn get_option_tallies)]
+	// OptionTallies holds, for the topics which have options, the number of votes of each option.
+	pub(super) type OptionTallies<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		T::BlockNumber,
+		Identity,
+		T::Hash,
+		Vec<u32>,
+		ValueQuery,
+	>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn get_winning_option)]
+	pub(super) type WinningOptions<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::BlockNumber, u32, OptionQuery>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn settlement_mode)]
+	pub(super) type Mode<T: Config> = StorageValue<_, SettlementMode, ValueQuery>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn balance_weighting)]
+	// BalanceWeighting tells whether the eras starting from now on are weighted by balance.
+	pub(super) type BalanceWeighting<T: Config> = StorageValue<_, bool, ValueQuery>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn get_snapshot_block)]
+	// SnapshotBlocks holds, for each balance weighted era, the block whose balances the era's
+	// votes are weighted by, recorded when the era starts.
+	pub(super) type SnapshotBlocks<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::BlockNumber, T::BlockNumber, OptionQuery>;
+
+	#[pallet::storage]
+	// BalanceSnapshots holds the free balance of an account at the latest snapshot block after
+	// which its balance changed, recorded right before the first change. An account whose balance
+	// didn't change since the current snapshot block still holds its balance at that block.
+	pub(super) type BalanceSnapshots<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, (T::BlockNumber, BalanceOf<T>), OptionQuery>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn get_vote_root)]
+	// VoteRoots holds, for each finalized era, the Merkle root over its (voter, topic, count)
+	// records, so that votes can be audited after the raw vote storage is pruned.
+	pub(super) type VoteRoots<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::BlockNumber, T::Hash, OptionQuery>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn total_topics_submitted)]
+	pub(super) type TotalTopicsSubmitted<T: Config> = StorageValue<_, u64, ValueQuery>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn total_votes_cast)]
+	pub(super) type TotalVotesCast<T: Config> = StorageValue<_, u64, ValueQuery>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn total_fees_collected)]
+	// TotalFeesCollected holds the sum of all vote fees charged, refunds and rebates aside.
+	pub(super) type TotalFeesCollected<T: Config> = StorageValue<_, BalanceOf<T>, ValueQuery>;
+
+	#[pallet::storage]
+	#[pallet::getter(fn total_eras_finalized)]
+	// TotalErasFinalized doesn't count the eras which were cancelled.
+	pub(super) type TotalErasFinalized<T: Config> = StorageValue<_, u32, ValueQuery>;
+
 	#[pallet::hooks]
 	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
 		// on_initialize, we would perform the era book keeping. If it's the beginning of a new era,
@@ -146,7 +432,7 @@ pub mod pallet {
 		// then then the next era storage is cleared, to prepare for accepting new proposals. and
 		// the topics moved to the current era to be voted for.
 		fn on_initialize(block_number: T::BlockNumber) -> Weight {
-			let weight = 0x0;
+			let mut weight = 0x0;
 			let era_duration = T::EraDuration::get();
 
 			if (block_number % era_duration).is_zero() {
@@ -155,18 +441,33 @@ pub mod pallet {
 				let prev_era = ((block_number - T::OneBlock::get()) / era_duration) * era_duration;
 				let votes = <Votes<T>>::get(prev_era).unwrap_or(vec![]);
 
-				let (topics, _): (Vec<T::Hash>, Vec<T::AccountId>) =
-					votes.clone().into_iter().unzip();
+				let mut counts: BTreeMap<&T::Hash, u128> = BTreeMap::new();
+				for (word, who) in votes.iter() {
+					*counts.entry(word).or_insert(0) += Self::vote_weight(prev_era, who);
+				}
+
+				weight = Self::settlement_weight(
+					votes.len() as u64,
+					<VoterCount<T>>::get(prev_era) as u64,
+					counts.len() as u64,
+				);
 
-				let mut counts = BTreeMap::new();
-				for word in topics.iter() {
-					*counts.entry(word).or_insert(0) += 1;
+				if <Mode<T>>::get() == SettlementMode::BudgetAllocation {
+					Self::allocate_budget(prev_era, &counts);
+				} else {
+					match counts.iter().max_by_key(|entry| entry.1) {
+						None => (),
+						Some((key, _)) => Self::settle_winner(prev_era, **key),
+					};
 				}
 
-				match counts.iter().max_by_key(|entry| entry.1) {
-					None => (),
-					Some((key, _)) => <Winners<T>>::set(prev_era, Some(**key)),
-				};
+				if let Some(root) = Self::merkle_root(Self::vote_leaves(prev_era)) {
+					<VoteRoots<T>>::insert(prev_era, root);
+				}
+
+				if !<CancelledEras<T>>::get(prev_era) {
+					<TotalErasFinalized<T>>::mutate(|total| *total = total.saturating_add(1));
+				}
 
 				//  New era is starting.
 				let nextera_hashes = <TopicsNextEra<T>>::get();
@@ -174,29 +475,55 @@ pub mod pallet {
 				// set the items in the next era into the current era, preparing for voting
 				<TopicsCurrEra<T>>::set(nextera_hashes);
 
+				if <BalanceWeighting<T>>::get() {
+					<SnapshotBlocks<T>>::insert(block_number, block_number);
+				}
+
 				// Set the topics in next era to empty
 				<TopicsNextEra<T>>::set(None);
 			};
 
 			weight
 		}
+
+		fn on_runtime_upgrade() -> Weight {
+			crate::migrations::v1::migrate::<T>()
+		}
+
+		#[cfg(feature = "offchain-notify")]
+		fn offchain_worker(block_number: T::BlockNumber) {
+			crate::notify::notify_era_result::<T>(block_number);
+		}
 	}
 
 	#[pallet::call]
 	impl<T: Config> Pallet<T> {
 		#[pallet::weight(1_0)]
-		pub fn submit_topic(origin: OriginFor<T>, topic_bytes: Vec<u8>) -> DispatchResult {
-			let who = ensure_signed(origin)?;
+		pub fn submit_topic(
+			origin: OriginFor<T>,
+			topic_bytes: Vec<u8>,
+			options: Vec<Vec<u8>>,
+		) -> DispatchResult {
+			let who = T::SubmitOrigin::ensure_origin(origin)?;
 			let topic_hash = T::Hashing::hash(&topic_bytes[..]);
 			ensure!(!<Topics<T>>::contains_key(&topic_hash), Error::<T>::DuplicateTopic);
+			ensure!(
+				options.len() <= T::MaxTopicOptions::get() as usize,
+				Error::<T>::TooManyOptions
+			);
 
 			// FIXME: Either make the amaount a constant or a function of the size of their topic
-			let deposit = <BalanceOf<T>>::from(10 as u32);
+			let deposit = if T::FeeExempt::contains(&who) {
+				Zero::zero()
+			} else {
+				<BalanceOf<T>>::from(10 as u32)
+			};
 			T::Currency::reserve(&who, deposit)
 				.map_err(|_| "locker can't afford to lock the amount requested")?;
 
 			let now = <frame_system::Pallet<T>>::block_number();
-			let topic = Topic { data: topic_bytes, provider: who.clone(), deposit, since: now };
+			let topic =
+				Topic { data: topic_bytes, provider: who.clone(), deposit, since: now, options };
 
 			// Insert the topic into the general list of topics.
 			<Topics<T>>::insert(topic_hash, topic);
@@ -207,18 +534,27 @@ pub mod pallet {
 
 			// Add topic to the next era.
 			<TopicsNextEra<T>>::append(topic_hash);
+			<TotalTopicsSubmitted<T>>::mutate(|total| *total = total.saturating_add(1));
 
 			Self::deposit_event(Event::<T>::NewTopic { who, topic_hash, deposit });
 			Ok(())
 		}
 
 		#[pallet::weight(1_0 + T::DbWeight::get().writes(1))]
-		pub fn vote_topic(origin: OriginFor<T>, topic_hash: T::Hash) -> DispatchResultWithPostInfo {
-			let who = ensure_signed(origin)?;
+		pub fn vote_topic(
+			origin: OriginFor<T>,
+			topic_hash: T::Hash,
+			option: Option<u32>,
+		) -> DispatchResultWithPostInfo {
+			let who = T::VoteOrigin::ensure_origin(origin)?;
 
-			let era_duration = T::EraDuration::get();
 			let block_number = <frame_system::Pallet<T>>::block_number();
-			let curr_era = ((block_number % era_duration) + era_duration) % era_duration;
+			let curr_era = Self::era_of(block_number);
+			ensure!(!<CancelledEras<T>>::get(curr_era), Error::<T>::EraIsCancelled);
+			// Only the topics of the current era can be voted for, not queued or past ones.
+			let active_topics = <TopicsCurrEra<T>>::get().unwrap_or(vec![]);
+			ensure!(active_topics.contains(&topic_hash), Error::<T>::InvalidTopicHash);
+			let option_count = Self::ensure_valid_option(&topic_hash, option)?;
 
 			let votes = <Votes<T>>::get(curr_era).unwrap_or(vec![]);
 			let (votes_by_topic_who, votes_by_who) =
@@ -233,18 +569,570 @@ pub mod pallet {
 				});
 			ensure!(votes_by_who <= T::MaxVotes::get(), Error::<T>::VoterReachedMaxVotes);
 
-			// NOTE: this is the number of votes plus 1 squared, to represent quadratic voting
-			let fee = 10;
-			let quadratic_voting_fee = ((votes_by_topic_who + 1) ^ 2) * fee;
-			let deposit = <BalanceOf<T>>::from(quadratic_voting_fee as u32);
+			if !Self::try_admit_voter(curr_era, &who)? {
+				// The era is full, the voter has been queued and no fee is charged.
+				return Ok(().into())
+			}
+
+			let deposit = Self::vote_fee(&who, votes_by_topic_who);
 			T::Currency::reserve(&who, deposit)?;
 
 			// Actually register a vote for the topic
-			<Votes<T>>::append(block_number, (topic_hash, &who));
+			<Votes<T>>::append(curr_era, (topic_hash, &who));
+			<VoteReceipts<T>>::append(
+				curr_era,
+				&who,
+				VoteReceipt { topic: topic_hash, option, fee: deposit },
+			);
+			if let Some(index) = option {
+				<OptionTallies<T>>::mutate(curr_era, topic_hash, |tallies| {
+					tallies.resize(option_count, 0);
+					tallies[index as usize] += 1;
+				});
+			}
+			<TotalVotesCast<T>>::mutate(|total| *total = total.saturating_add(1));
+			<TotalFeesCollected<T>>::mutate(|total| *total = total.saturating_add(deposit));
 
-			Self::deposit_event(Event::<T>::NewVote { who, topic_hash });
+			Self::deposit_event(Event::<T>::NewVote { who, topic_hash, option });
 
 			Ok(().into())
 		}
+
+		#[pallet::weight(1_0 + T::DbWeight::get().writes(4))]
+		pub fn withdraw_votes(origin: OriginFor<T>) -> DispatchResult {
+			let who = T::VoteOrigin::ensure_origin(origin)?;
+
+			let curr_era = Self::era_of(<frame_system::Pallet<T>>::block_number());
+			ensure!(<EraVoters<T>>::get(curr_era, &who), Error::<T>::NotAVoter);
+
+			let receipts = <VoteReceipts<T>>::take(curr_era, &who);
+			let refund = receipts
+				.iter()
+				.fold(Zero::zero(), |acc: BalanceOf<T>, receipt| acc + receipt.fee);
+			T::Currency::unreserve(&who, refund);
+			for receipt in receipts.iter() {
+				if let Some(index) = receipt.option {
+					<OptionTallies<T>>::mutate(curr_era, receipt.topic, |tallies| {
+						if let Some(votes) = tallies.get_mut(index as usize) {
+							*votes = votes.saturating_sub(1);
+						}
+					});
+				}
+			}
+
+			<Votes<T>>::mutate(curr_era, |votes| {
+				if let Some(votes) = votes {
+					votes.retain(|(_, voter)| voter != &who);
+				}
+			});
+			<EraVoters<T>>::remove(curr_era, &who);
+			<VoterCount<T>>::mutate(curr_era, |count| *count = count.saturating_sub(1));
+
+			Self::deposit_event(Event::<T>::VotesWithdrawn { who, era: curr_era, refund });
+
+			// A slot has been freed, hand it over to the first account on the waitlist.
+			let mut waitlist = <Waitlist<T>>::get(curr_era);
+			let has_room = T::MaxVotersPerEra::get()
+				.map_or(true, |max_voters| <VoterCount<T>>::get(curr_era) < max_voters);
+			if has_room && !waitlist.is_empty() {
+				let next = waitlist.remove(0);
+				<Waitlist<T>>::insert(curr_era, waitlist);
+				<EraVoters<T>>::insert(curr_era, &next, true);
+				<VoterCount<T>>::mutate(curr_era, |count| *count += 1);
+				Self::deposit_event(Event::<T>::VoterAdmitted { who: next, era: curr_era });
+			}
+
+			Ok(())
+		}
+
+		#[pallet::weight(1_0 + T::DbWeight::get().writes(4))]
+		pub fn change_vote(
+			origin: OriginFor<T>,
+			from_topic: T::Hash,
+			to_topic: T::Hash,
+			n: u32,
+			to_option: Option<u32>,
+		) -> DispatchResult {
+			let who = T::VoteOrigin::ensure_origin(origin)?;
+
+			let curr_era = Self::era_of(<frame_system::Pallet<T>>::block_number());
+			ensure!(!<CancelledEras<T>>::get(curr_era), Error::<T>::EraIsCancelled);
+			ensure!(from_topic != to_topic, Error::<T>::SameTopic);
+			let active_topics = <TopicsCurrEra<T>>::get().unwrap_or(vec![]);
+			ensure!(
+				active_topics.contains(&from_topic) && active_topics.contains(&to_topic),
+				Error::<T>::InvalidTopicHash
+			);
+			let option_count = Self::ensure_valid_option(&to_topic, to_option)?;
+
+			// Take back the latest votes on `from_topic`, which are the most expensive ones.
+			let mut receipts = <VoteReceipts<T>>::get(curr_era, &who);
+			let mut moved = vec![];
+			let mut index = receipts.len();
+			while index > 0 && (moved.len() as u32) < n {
+				index -= 1;
+				if receipts[index].topic == from_topic {
+					moved.push(receipts.remove(index));
+				}
+			}
+			ensure!(!moved.is_empty(), Error::<T>::NoVotesToMove);
+			let count = moved.len() as u32;
+
+			// Price the moved votes again, as if they were cast on `to_topic` now.
+			let refund =
+				moved.iter().fold(Zero::zero(), |acc: BalanceOf<T>, receipt| acc + receipt.fee);
+			let votes_on_to_topic =
+				receipts.iter().filter(|receipt| receipt.topic == to_topic).count() as u32;
+			let mut charge: BalanceOf<T> = Zero::zero();
+			for nth_vote in votes_on_to_topic..votes_on_to_topic + count {
+				let fee = Self::vote_fee(&who, nth_vote);
+				charge = charge.saturating_add(fee);
+				receipts.push(VoteReceipt { topic: to_topic, option: to_option, fee });
+			}
+			if charge > refund {
+				T::Currency::reserve(&who, charge - refund)?;
+			} else {
+				T::Currency::unreserve(&who, refund - charge);
+			}
+			<VoteReceipts<T>>::insert(curr_era, &who, receipts);
+
+			<Votes<T>>::mutate(curr_era, |votes| {
+				let votes = votes.get_or_insert_with(Vec::new);
+				let mut to_remove = count;
+				let mut index = votes.len();
+				while index > 0 && to_remove > 0 {
+					index -= 1;
+					if votes[index].0 == from_topic && votes[index].1 == who {
+						votes.remove(index);
+						to_remove -= 1;
+					}
+				}
+				for _ in 0..count {
+					votes.push((to_topic, who.clone()));
+				}
+			});
+
+			for receipt in moved.iter() {
+				if let Some(index) = receipt.option {
+					<OptionTallies<T>>::mutate(curr_era, from_topic, |tallies| {
+						if let Some(votes) = tallies.get_mut(index as usize) {
+							*votes = votes.saturating_sub(1);
+						}
+					});
+				}
+			}
+			if let Some(index) = to_option {
+				<OptionTallies<T>>::mutate(curr_era, to_topic, |tallies| {
+					tallies.resize(option_count, 0);
+					tallies[index as usize] += count;
+				});
+			}
+			// Only what is charged on top of the refunded fees is newly collected.
+			<TotalFeesCollected<T>>::mutate(|total| {
+				*total = total.saturating_add(charge.saturating_sub(refund))
+			});
+
+			Self::deposit_event(Event::<T>::VotesChanged { who, from_topic, to_topic, count });
+			Ok(())
+		}
+
+		// Each voter costs a receipt, a voter flag and a reserve, and each topic costs its tallies,
+		// the topic itself and its provider's reserve.
+		#[pallet::weight(
+			1_0 + T::DbWeight::get().reads_writes(
+				4 + 2 * (*voter_count as u64) + 3 * (*topic_count as u64),
+				6 + 3 * (*voter_count as u64) + 3 * (*topic_count as u64),
+			)
+		)]
+		pub fn cancel_era(
+			origin: OriginFor<T>,
+			voter_count: u32,
+			topic_count: u32,
+		) -> DispatchResult {
+			T::AdminOrigin::ensure_origin(origin)?;
+
+			let curr_era = Self::era_of(<frame_system::Pallet<T>>::block_number());
+			ensure!(!<CancelledEras<T>>::get(curr_era), Error::<T>::EraIsCancelled);
+			ensure!(voter_count >= <VoterCount<T>>::get(curr_era), Error::<T>::InvalidWitness);
+			let mut topics = <TopicsCurrEra<T>>::get().unwrap_or(vec![]);
+			ensure!(topic_count as usize >= topics.len(), Error::<T>::InvalidWitness);
+			<CancelledEras<T>>::insert(curr_era, true);
+
+			// Refund the topic deposits and queue the topics up again for the next era, ahead of
+			// the ones submitted during this era.
+			<TopicsCurrEra<T>>::kill();
+			for topic_hash in topics.iter() {
+				<Topics<T>>::mutate(topic_hash, |topic| {
+					if let Some(topic) = topic {
+						T::Currency::unreserve(&topic.provider, topic.deposit);
+						topic.deposit = Zero::zero();
+					}
+				});
+			}
+			topics.extend(<TopicsNextEra<T>>::get().unwrap_or(vec![]));
+			<TopicsNextEra<T>>::set(if topics.is_empty() { None } else { Some(topics) });
+
+			// Refund every vote fee, and forget about the era's voters.
+			for (who, receipts) in <VoteReceipts<T>>::drain_prefix(curr_era) {
+				let refund = receipts
+					.iter()
+					.fold(Zero::zero(), |acc: BalanceOf<T>, receipt| acc + receipt.fee);
+				T::Currency::unreserve(&who, refund);
+			}
+			<Votes<T>>::remove(curr_era);
+			<OptionTallies<T>>::drain_prefix(curr_era).for_each(drop);
+			<EraVoters<T>>::drain_prefix(curr_era).for_each(drop);
+			<VoterCount<T>>::remove(curr_era);
+			<Waitlist<T>>::remove(curr_era);
+
+			Self::deposit_event(Event::<T>::EraCancelled { era: curr_era });
+			Ok(())
+		}
+
+		#[pallet::weight(1_0 + T::DbWeight::get().writes(1))]
+		pub fn set_settlement_mode(origin: OriginFor<T>, mode: SettlementMode) -> DispatchResult {
+			T::AdminOrigin::ensure_origin(origin)?;
+
+			<Mode<T>>::put(mode);
+
+			Self::deposit_event(Event::<T>::SettlementModeSet { mode });
+			Ok(())
+		}
+
+		#[pallet::weight(1_0 + T::DbWeight::get().writes(1))]
+		pub fn set_balance_weighting(origin: OriginFor<T>, enabled: bool) -> DispatchResult {
+			T::AdminOrigin::ensure_origin(origin)?;
+
+			<BalanceWeighting<T>>::put(enabled);
+
+			Self::deposit_event(Event::<T>::BalanceWeightingSet { enabled });
+			Ok(())
+		}
+	}
+
+	impl<T: Config> Pallet<T> {
+		/// Returns the account of the pot which era budgets are paid from.
+		pub fn account_id() -> T::AccountId {
+			T::PalletId::get().into_account_truncating()
+		}
+
+		/// Returns all the running totals kept by the pallet at once.
+		pub fn metrics() -> Metrics<BalanceOf<T>> {
+			Metrics {
+				topics_submitted: <TotalTopicsSubmitted<T>>::get(),
+				votes_cast: <TotalVotesCast<T>>::get(),
+				fees_collected: <TotalFeesCollected<T>>::get(),
+				eras_finalized: <TotalErasFinalized<T>>::get(),
+			}
+		}
+
+		/// Returns the block number at which the era containing `block_number` started.
+		pub fn era_of(block_number: T::BlockNumber) -> T::BlockNumber {
+			let era_duration = T::EraDuration::get();
+			(block_number / era_duration) * era_duration
+		}
+
+		/// Returns the (voter, topic, count) records of `era`, sorted by voter and then topic. This
+		/// is the order in which they are committed to in the era's vote root.
+		pub fn vote_records(era: T::BlockNumber) -> Vec<(T::AccountId, T::Hash, u32)> {
+			let mut counts = BTreeMap::<(T::AccountId, T::Hash), u32>::new();
+			for (topic_hash, who) in <Votes<T>>::get(era).unwrap_or(vec![]) {
+				*counts.entry((who, topic_hash)).or_insert(0) += 1;
+			}
+			counts
+				.into_iter()
+				.map(|((who, topic_hash), count)| (who, topic_hash, count))
+				.collect()
+		}
+
+		/// Returns the leaf committed to in a vote root for `who` casting `count` votes on
+		/// `topic_hash`.
+		pub fn vote_leaf(who: &T::AccountId, topic_hash: &T::Hash, count: u32) -> T::Hash {
+			T::Hashing::hash_of(&(who, topic_hash, count))
+		}
+
+		/// Builds the proof that `who` voted for `topic_hash` in `era`, along with their number of
+		/// votes. Only possible while the votes of the era are still in storage.
+		pub fn vote_proof(
+			era: T::BlockNumber,
+			who: &T::AccountId,
+			topic_hash: &T::Hash,
+		) -> Option<(u32, Vec<T::Hash>)> {
+			let records = Self::vote_records(era);
+			let mut index = records
+				.iter()
+				.position(|(voter, topic, _)| voter == who && topic == topic_hash)?;
+			let count = records[index].2;
+
+			let mut layer = Self::vote_leaves(era);
+			let mut proof = vec![];
+			while layer.len() > 1 {
+				// The last node of an odd layer has no sibling, it is moved up as is.
+				if let Some(sibling) = layer.get(index ^ 1) {
+					proof.push(*sibling);
+				}
+				layer = Self::merkle_parents(&layer);
+				index /= 2;
+			}
+
+			Some((count, proof))
+		}
+
+		/// Checks `proof` that `who` cast `count` votes for `topic_hash` against the vote root of
+		/// `era`.
+		pub fn verify_vote(
+			era: T::BlockNumber,
+			who: &T::AccountId,
+			topic_hash: &T::Hash,
+			count: u32,
+			proof: &[T::Hash],
+		) -> bool {
+			let leaf = Self::vote_leaf(who, topic_hash, count);
+			let root = proof.iter().fold(leaf, |node, sibling| Self::merkle_hash(&node, sibling));
+			<VoteRoots<T>>::get(era) == Some(root)
+		}
+
+		fn vote_leaves(era: T::BlockNumber) -> Vec<T::Hash> {
+			Self::vote_records(era)
+				.iter()
+				.map(|(who, topic_hash, count)| Self::vote_leaf(who, topic_hash, *count))
+				.collect()
+		}
+
+		fn merkle_root(mut layer: Vec<T::Hash>) -> Option<T::Hash> {
+			while layer.len() > 1 {
+				layer = Self::merkle_parents(&layer);
+			}
+			layer.pop()
+		}
+
+		fn merkle_parents(layer: &[T::Hash]) -> Vec<T::Hash> {
+			layer
+				.chunks(2)
+				.map(|pair| match pair {
+					[left, right] => Self::merkle_hash(left, right),
+					_ => pair[0],
+				})
+				.collect()
+		}
+
+		// Siblings are hashed in sorted order, so that proofs don't need to tell left from right.
+		fn merkle_hash(a: &T::Hash, b: &T::Hash) -> T::Hash {
+			if a <= b {
+				T::Hashing::hash_of(&(a, b))
+			} else {
+				T::Hashing::hash_of(&(b, a))
+			}
+		}
+
+		// Returns the fee `who` pays for a new vote on a topic they already have `votes_on_topic`
+		// votes on.
+		fn vote_fee(who: &T::AccountId, votes_on_topic: u32) -> BalanceOf<T> {
+			if T::FeeExempt::contains(who) {
+				return Zero::zero()
+			}
+
+			// NOTE: this is the number of votes plus 1 squared, to represent quadratic voting
+			let fee = 10;
+			let quadratic_voting_fee = (votes_on_topic + 1).saturating_pow(2).saturating_mul(fee);
+			<BalanceOf<T>>::from(quadratic_voting_fee)
+		}
+
+		// Checks that `option` picks one of the options of the topic, or is missing if the topic
+		// has none. Returns the number of options of the topic.
+		fn ensure_valid_option(
+			topic_hash: &T::Hash,
+			option: Option<u32>,
+		) -> Result<usize, DispatchError> {
+			let topic = <Topics<T>>::get(topic_hash).ok_or(Error::<T>::InvalidTopicHash)?;
+			let option_count = topic.options.len();
+			let valid = match option {
+				Some(index) => (index as usize) < option_count,
+				None => option_count == 0,
+			};
+			ensure!(valid, Error::<T>::InvalidOption);
+			Ok(option_count)
+		}
+
+		// Returns the weight of settling an era of `votes` votes cast by `voters` voters on `topics`
+		// topics, whichever the settlement mode. Each vote costs its weight, each voter their vote
+		// receipts, weight and rebate, and each topic its provider's payout. Hashing the vote root
+		// is negligible next to those storage accesses.
+		fn settlement_weight(votes: u64, voters: u64, topics: u64) -> Weight {
+			T::DbWeight::get()
+				.reads_writes(10 + 3 * votes + 6 * voters + 3 * topics, 8 + 2 * voters + 3 * topics)
+		}
+
+		// Records `winner` as the winning topic of `era`, along with its winning option if it has
+		// options, and rebates its voters.
+		fn settle_winner(era: T::BlockNumber, winner: T::Hash) {
+			<Winners<T>>::set(era, Some(winner));
+
+			// Ties go to the option with the lowest index. The options are weighed before the
+			// rebates change the balances they can be weighted by.
+			let tallies = Self::option_weights(era, winner);
+			if let Some(most_votes) = tallies.iter().max() {
+				let winning_option = tallies.iter().position(|votes| votes == most_votes);
+				<WinningOptions<T>>::set(era, winning_option.map(|i| i as u32));
+			}
+
+			Self::rebate_winning_voters(era, winner);
+		}
+
+		// Returns how much each option of `topic_hash` counts for in `era`. In a weighted era the
+		// options are weighed like the topics, by the balance snapshots of their voters.
+		fn option_weights(era: T::BlockNumber, topic_hash: T::Hash) -> Vec<u128> {
+			if !<SnapshotBlocks<T>>::contains_key(era) {
+				return <OptionTallies<T>>::get(era, topic_hash)
+					.into_iter()
+					.map(u128::from)
+					.collect()
+			}
+
+			let mut weights = Vec::new();
+			for (who, receipts) in <VoteReceipts<T>>::iter_prefix(era) {
+				let weight = Self::vote_weight(era, &who);
+				for receipt in receipts.iter().filter(|receipt| receipt.topic == topic_hash) {
+					if let Some(index) = receipt.option {
+						let index = index as usize;
+						if weights.len() <= index {
+							weights.resize(index + 1, 0);
+						}
+						weights[index] = weights[index].saturating_add(weight);
+					}
+				}
+			}
+			weights
+		}
+
+		// Splits the era budget across the topics which got at least `AllocationThreshold` of the
+		// votes of `era`, in proportion to their votes, and pays each share to the topic provider.
+		// If the pot holds less than the budget, what it holds is split instead.
+		fn allocate_budget(era: T::BlockNumber, counts: &BTreeMap<&T::Hash, u128>) {
+			let total_votes = counts.values().fold(0u128, |acc, votes| acc.saturating_add(*votes));
+			let min_votes = T::AllocationThreshold::get().mul_ceil(total_votes);
+			let eligible: Vec<(T::Hash, u128)> = counts
+				.iter()
+				.filter(|(_, votes)| **votes >= min_votes)
+				.map(|(topic_hash, votes)| (**topic_hash, *votes))
+				.collect();
+			let eligible_votes =
+				eligible.iter().fold(0u128, |acc, (_, votes)| acc.saturating_add(*votes));
+			if eligible_votes == 0 {
+				return
+			}
+
+			let pot = Self::account_id();
+			let budget: u128 =
+				T::EraBudget::get().min(T::Currency::free_balance(&pot)).saturated_into();
+			for (topic_hash, votes) in eligible {
+				let provider = match <Topics<T>>::get(topic_hash) {
+					Some(topic) => topic.provider,
+					None => continue,
+				};
+				// Votes can be balance weighted, so `budget * votes` doesn't necessarily fit in a u128.
+				let share = Perquintill::from_rational(votes, eligible_votes);
+				let amount: BalanceOf<T> = (share * budget).saturated_into();
+				let paid = T::Currency::transfer(
+					&pot,
+					&provider,
+					amount,
+					ExistenceRequirement::AllowDeath,
+				);
+				if paid.is_ok() {
+					Self::deposit_event(Event::<T>::BudgetAllocated { era, topic_hash, amount });
+				}
+			}
+		}
+
+		/// Returns the free balance of `who` at the snapshot block of `era`, or `None` if `era`
+		/// isn't weighted by balance. Only reliable until the first block of the next era is
+		/// initialized, which is when `era` is settled.
+		pub fn balance_at_snapshot(
+			era: T::BlockNumber,
+			who: &T::AccountId,
+		) -> Option<BalanceOf<T>> {
+			let snapshot_block = <SnapshotBlocks<T>>::get(era)?;
+			match <BalanceSnapshots<T>>::get(who) {
+				Some((block, balance)) if block == snapshot_block => Some(balance),
+				// The balance hasn't changed since the snapshot block.
+				_ => Some(T::Currency::free_balance(who)),
+			}
+		}
+
+		/// Records `free` as the balance of `who` at the snapshot block of the current era, unless
+		/// it is already recorded. Called by `SnapshotAccountStore` right before the balance of
+		/// `who` changes.
+		pub(crate) fn note_balance_change(who: &T::AccountId, free: BalanceOf<T>) {
+			let curr_era = Self::era_of(<frame_system::Pallet<T>>::block_number());
+			if let Some(snapshot_block) = <SnapshotBlocks<T>>::get(curr_era) {
+				let recorded = <BalanceSnapshots<T>>::get(who)
+					.map_or(false, |(block, _)| block == snapshot_block);
+				if !recorded {
+					<BalanceSnapshots<T>>::insert(who, (snapshot_block, free));
+				}
+			}
+		}
+
+		// Returns how much a single vote of `who` counts for in `era`: their balance at the
+		// snapshot block if the era is weighted by balance, 1 otherwise.
+		fn vote_weight(era: T::BlockNumber, who: &T::AccountId) -> u128 {
+			Self::balance_at_snapshot(era, who).map_or(1, |balance| balance.saturated_into())
+		}
+
+		// Unreserves `WinnerRebate` of the fees each voter paid for the winning topic of `era`, so
+		// the rebate is proportional to how much they backed it.
+		fn rebate_winning_voters(era: T::BlockNumber, winner: T::Hash) {
+			let rebate = T::WinnerRebate::get();
+			if rebate.is_zero() {
+				return
+			}
+
+			for (who, receipts) in <VoteReceipts<T>>::iter_prefix(era) {
+				let paid = receipts
+					.iter()
+					.filter(|receipt| receipt.topic == winner)
+					.fold(Zero::zero(), |acc: BalanceOf<T>, receipt| acc + receipt.fee);
+				let amount = rebate * paid;
+				if amount.is_zero() {
+					continue
+				}
+
+				T::Currency::unreserve(&who, amount);
+				Self::deposit_event(Event::<T>::FeeRebated { who, era, amount });
+			}
+		}
+
+		// Admits `who` as a voter in `era` if there is room for them. Accounts which are already
+		// waitlisted have priority over newcomers, so a newcomer only gets in if the free slots
+		// outnumber the waitlist. Returns false if `who` was added to the waitlist instead.
+		fn try_admit_voter(era: T::BlockNumber, who: &T::AccountId) -> Result<bool, DispatchError> {
+			if <EraVoters<T>>::get(era, who) {
+				return Ok(true)
+			}
+
+			if let Some(max_voters) = T::MaxVotersPerEra::get() {
+				let free = max_voters.saturating_sub(<VoterCount<T>>::get(era)) as usize;
+				let mut waitlist = <Waitlist<T>>::get(era);
+				match waitlist.iter().position(|waiting| waiting == who) {
+					Some(position) if position < free => {
+						waitlist.remove(position);
+						<Waitlist<T>>::insert(era, waitlist);
+					},
+					Some(_) => return Err(Error::<T>::StillWaitlisted.into()),
+					None if waitlist.len() < free => (),
+					None => {
+						waitlist.try_push(who.clone()).map_err(|_| Error::<T>::WaitlistFull)?;
+						<Waitlist<T>>::insert(era, waitlist);
+						Self::deposit_event(Event::<T>::VoterWaitlisted { who: who.clone(), era });
+						return Ok(false)
+					},
+				}
+			}
+
+			<EraVoters<T>>::insert(era, who, true);
+			<VoterCount<T>>::mutate(era, |count| *count += 1);
+			Ok(true)
+		}
 	}
 }
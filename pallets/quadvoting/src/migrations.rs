@@ -0,0 +1,44 @@
+//! Storage migrations of the quadvoting pallet.
+
+pub mod v1 {
+	use crate::pallet::{BalanceOf, Config, Pallet, Topic, Topics};
+	use codec::Decode;
+	use frame_support::{
+		traits::{Get, GetStorageVersion, StorageVersion},
+		weights::Weight,
+	};
+	use sp_std::vec::Vec;
+
+	// A topic as stored before topics could carry options.
+	#[derive(Decode)]
+	struct OldTopic<AccountId, Balance, BlockNumber> {
+		data: Vec<u8>,
+		provider: AccountId,
+		deposit: Balance,
+		since: BlockNumber,
+	}
+
+	/// Gives every topic stored before version 1 an empty list of options.
+	pub fn migrate<T: Config>() -> Weight {
+		if Pallet::<T>::on_chain_storage_version() >= 1 {
+			return 0
+		}
+
+		let mut translated = 0u64;
+		<Topics<T>>::translate::<OldTopic<T::AccountId, BalanceOf<T>, T::BlockNumber>, _>(
+			|_, old| {
+				translated += 1;
+				Some(Topic {
+					data: old.data,
+					provider: old.provider,
+					deposit: old.deposit,
+					since: old.since,
+					options: Vec::new(),
+				})
+			},
+		);
+		StorageVersion::new(1).put::<Pallet<T>>();
+
+		T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+	}
+}
@@ -1,10 +1,15 @@
 use crate as pallet_quadvoting;
-use frame_support::traits::{ConstU16, ConstU64};
+use frame_support::{
+	parameter_types,
+	traits::{ConstU16, ConstU32, ConstU64, Contains},
+	PalletId,
+};
 use frame_system as system;
 use sp_core::H256;
 use sp_runtime::{
 	testing::Header,
 	traits::{BlakeTwo256, IdentityLookup},
+	Percent,
 };
 
 type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
@@ -33,7 +38,8 @@ impl pallet_balances::Config for Test {
 	type DustRemoval = ();
 	type Event = Event;
 	type ExistentialDeposit = ConstU64<1>;
-	type AccountStore = System;
+	// Records balances for the snapshots of balance weighted eras.
+	type AccountStore = pallet_quadvoting::snapshot::SnapshotAccountStore<Test, System>;
 	type WeightInfo = ();
 }
 
@@ -64,12 +70,38 @@ impl system::Config for Test {
 	type MaxConsumers = frame_support::traits::ConstU32<16>;
 }
 
+parameter_types! {
+	pub const MaxVotersPerEra: Option<u32> = Some(2);
+	pub const WinnerRebate: Percent = Percent::from_percent(50);
+	pub const QuadVotingPalletId: PalletId = PalletId(*b"py/qdvot");
+	pub const AllocationThreshold: Percent = Percent::from_percent(20);
+}
+
+/// Account 5 can barely pay for anything, so it is the one exempted from fees.
+pub struct FeeExemptAccounts;
+impl Contains<u64> for FeeExemptAccounts {
+	fn contains(who: &u64) -> bool {
+		*who == 5
+	}
+}
+
 impl pallet_quadvoting::Config for Test {
 	type Event = Event;
 	type EraDuration = ConstU64<20>;
 	type MaxVotes = ConstU16<10>;
 	type Currency = Balances;
 	type OneBlock = ConstU64<1>;
+	type MaxVotersPerEra = MaxVotersPerEra;
+	type MaxWaitlist = ConstU32<1>;
+	type WinnerRebate = WinnerRebate;
+	type MaxTopicOptions = ConstU32<4>;
+	type PalletId = QuadVotingPalletId;
+	type EraBudget = ConstU64<90>;
+	type AllocationThreshold = AllocationThreshold;
+	type FeeExempt = FeeExemptAccounts;
+	type SubmitOrigin = system::EnsureSigned<u64>;
+	type VoteOrigin = system::EnsureSigned<u64>;
+	type AdminOrigin = system::EnsureRoot<u64>;
 }
 
 // Build genesis storage according to the mock runtime.
@@ -0,0 +1,170 @@
+/*
+	Era result notifications
+	========================
+
+	With the `offchain-notify` feature enabled, the off-chain worker POSTs a JSON summary of every
+	finalized era to the HTTP endpoints configured by the node operator, e.g.
+
+		{"era":20,"cancelled":false,"winner":"0x1234...","winning_option":null,"votes":4}
+
+	The endpoints are read from the persistent off-chain local storage under `WEBHOOKS_KEY`, as a
+	SCALE encoded `Vec<Vec<u8>>` of URLs. They can be set with the `offchain_localStorageSet` RPC.
+
+	Each run of the worker catches up on the eras finalized since the last one announced, so eras
+	aren't lost when the worker skips blocks, e.g. during a major sync. An era only counts as
+	announced once at least one endpoint accepted it, otherwise it is retried on the next run.
+*/
+
+use crate::{Config, Pallet};
+use frame_support::{
+	sp_io,
+	sp_runtime::{
+		offchain::{
+			http,
+			storage::StorageValueRef,
+			storage_lock::{StorageLock, Time},
+			Duration,
+		},
+		traits::{Saturating, UniqueSaturatedInto, Zero},
+	},
+	traits::Get,
+};
+use sp_std::{vec, vec::Vec};
+
+/// Off-chain local storage key holding the endpoints to notify.
+pub const WEBHOOKS_KEY: &[u8] = b"quadvoting::webhooks";
+
+// Off-chain local storage key holding the last era which was notified.
+const LAST_NOTIFIED_KEY: &[u8] = b"quadvoting::last-notified-era";
+
+// Off-chain local storage key of the lock keeping concurrent workers from notifying twice.
+const NOTIFY_LOCK_KEY: &[u8] = b"quadvoting::notify-lock";
+
+// The max number of eras announced in a single run, the rest is left to the next runs.
+const MAX_ERAS_PER_RUN: u32 = 5;
+
+// How long each endpoint has to accept the notification.
+const HTTP_TIMEOUT_MS: u64 = 3_000;
+
+/// Send the result of every era finalized as of `block_number`, and not announced yet, to every
+/// configured endpoint.
+pub(crate) fn notify_era_result<T: Config>(block_number: T::BlockNumber) {
+	let era_duration = T::EraDuration::get();
+	let curr_era = Pallet::<T>::era_of(block_number);
+	if curr_era.is_zero() {
+		return
+	}
+	let last_finalized = curr_era - era_duration;
+
+	let endpoints = match StorageValueRef::persistent(WEBHOOKS_KEY).get::<Vec<Vec<u8>>>() {
+		Ok(Some(endpoints)) if !endpoints.is_empty() => endpoints,
+		_ => return,
+	};
+
+	// The worker runs for every imported block, and those can be processed concurrently. The lock
+	// is held for as long as a run can take at most.
+	let run_ms = HTTP_TIMEOUT_MS * MAX_ERAS_PER_RUN as u64 * endpoints.len() as u64;
+	let mut lock =
+		StorageLock::<Time>::with_deadline(NOTIFY_LOCK_KEY, Duration::from_millis(run_ms));
+	let _guard = match lock.try_lock() {
+		Ok(guard) => guard,
+		Err(_) => return,
+	};
+
+	// Without any era announced yet, start from the last finalized one rather than from genesis.
+	let last_notified = StorageValueRef::persistent(LAST_NOTIFIED_KEY);
+	let mut era = match last_notified.get::<u64>() {
+		Ok(Some(last)) => {
+			let last: T::BlockNumber = last.unique_saturated_into();
+			last.saturating_add(era_duration)
+		},
+		_ => last_finalized,
+	};
+	for _ in 0..MAX_ERAS_PER_RUN {
+		if era > last_finalized {
+			break
+		}
+
+		let body = era_summary::<T>(era);
+		// A failing endpoint must not prevent the others from being notified.
+		let delivered = endpoints
+			.iter()
+			.fold(false, |delivered, endpoint| post(endpoint, &body).is_ok() || delivered);
+		if !delivered {
+			// Try again with the same era on the next run.
+			break
+		}
+		let era_number: u64 = era.unique_saturated_into();
+		last_notified.set(&era_number);
+		era = era.saturating_add(era_duration);
+	}
+}
+
+/// Builds the JSON summary of the result of `era`.
+pub(crate) fn era_summary<T: Config>(era: T::BlockNumber) -> Vec<u8> {
+	let mut json = b"{\"era\":".to_vec();
+	push_number(&mut json, era.unique_saturated_into());
+
+	json.extend_from_slice(b",\"cancelled\":");
+	let cancelled: &[u8] = if Pallet::<T>::is_era_cancelled(era) { b"true" } else { b"false" };
+	json.extend_from_slice(cancelled);
+
+	json.extend_from_slice(b",\"winner\":");
+	match Pallet::<T>::get_winners(era) {
+		Some(winner) => {
+			json.extend_from_slice(b"\"0x");
+			push_hex(&mut json, winner.as_ref());
+			json.push(b'"');
+		},
+		None => json.extend_from_slice(b"null"),
+	}
+
+	json.extend_from_slice(b",\"winning_option\":");
+	match Pallet::<T>::get_winning_option(era) {
+		Some(option) => push_number(&mut json, option.into()),
+		None => json.extend_from_slice(b"null"),
+	}
+
+	json.extend_from_slice(b",\"votes\":");
+	push_number(&mut json, Pallet::<T>::get_votes(era).map_or(0, |votes| votes.len() as u64));
+
+	json.push(b'}');
+	json
+}
+
+fn post(endpoint: &[u8], body: &[u8]) -> Result<(), http::Error> {
+	let url = sp_std::str::from_utf8(endpoint).map_err(|_| http::Error::Unknown)?;
+	let deadline = sp_io::offchain::timestamp().add(Duration::from_millis(HTTP_TIMEOUT_MS));
+
+	let pending = http::Request::post(url, vec![body])
+		.add_header("Content-Type", "application/json")
+		.deadline(deadline)
+		.send()
+		.map_err(|_| http::Error::IoError)?;
+	let response = pending.try_wait(deadline).map_err(|_| http::Error::DeadlineReached)??;
+
+	if response.code / 100 != 2 {
+		return Err(http::Error::Unknown)
+	}
+	Ok(())
+}
+
+fn push_number(out: &mut Vec<u8>, mut number: u64) {
+	let start = out.len();
+	loop {
+		out.push(b'0' + (number % 10) as u8);
+		number /= 10;
+		if number == 0 {
+			break
+		}
+	}
+	out[start..].reverse();
+}
+
+fn push_hex(out: &mut Vec<u8>, bytes: &[u8]) {
+	const DIGITS: &[u8; 16] = b"0123456789abcdef";
+	for byte in bytes {
+		out.push(DIGITS[(byte >> 4) as usize]);
+		out.push(DIGITS[(byte & 0x0f) as usize]);
+	}
+}
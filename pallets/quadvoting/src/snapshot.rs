@@ -0,0 +1,43 @@
+/*
+	Balance snapshots
+	=================
+
+	Balance weighted eras weigh votes by the free balances at the era's snapshot block. Rather
+	than copying every balance when the era starts, the snapshot is built lazily: the account
+	store of the balances pallet is wrapped by `SnapshotAccountStore`, which records the balance
+	of an account right before it first changes within the era. The runtime opts in with e.g.
+
+		impl pallet_balances::Config for Runtime {
+			type AccountStore = pallet_quadvoting::snapshot::SnapshotAccountStore<Runtime, System>;
+			...
+		}
+
+	This costs every balance change up to two more reads and a write.
+*/
+
+use crate::{pallet::BalanceOf, Config, Pallet};
+use frame_support::{sp_runtime::DispatchError, traits::StoredMap};
+use pallet_balances::AccountData;
+use sp_std::marker::PhantomData;
+
+/// An account store recording balances for the snapshots of weighted eras, before delegating
+/// to `Store`.
+pub struct SnapshotAccountStore<T, Store>(PhantomData<(T, Store)>);
+
+impl<T, Store> StoredMap<T::AccountId, AccountData<BalanceOf<T>>> for SnapshotAccountStore<T, Store>
+where
+	T: Config,
+	Store: StoredMap<T::AccountId, AccountData<BalanceOf<T>>>,
+{
+	fn get(who: &T::AccountId) -> AccountData<BalanceOf<T>> {
+		Store::get(who)
+	}
+
+	fn try_mutate_exists<R, E: From<DispatchError>>(
+		who: &T::AccountId,
+		f: impl FnOnce(&mut Option<AccountData<BalanceOf<T>>>) -> Result<R, E>,
+	) -> Result<R, E> {
+		Pallet::<T>::note_balance_change(who, Store::get(who).free);
+		Store::try_mutate_exists(who, f)
+	}
+}
@@ -1,5 +1,10 @@
-use crate::mock::*;
-use frame_support::{assert_ok, traits::Hooks};
+use crate::{mock::*, Error, SettlementMode};
+use codec::Encode;
+use frame_support::{
+	assert_noop, assert_ok, storage,
+	traits::{Currency, GetStorageVersion, Hooks, StorageVersion},
+};
+use sp_core::H256;
 
 /// Run until a particular block.
 pub fn run_to_block(n: u64) {
@@ -16,7 +21,7 @@ pub fn run_to_block(n: u64) {
 #[test]
 fn submit_topic_with_sufficient_funds() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(QuadVoting::submit_topic(Origin::signed(1), vec![0]));
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(1), vec![0], vec![]));
 	});
 }
 
@@ -34,7 +39,8 @@ fn test_voting_end_to_end() {
 		// Let's create new topics. New topics should be created on the next topics batch until
 		assert!(QuadVoting::submit_topic(
 			Origin::signed(1),
-			"new test topic 1".as_bytes().to_vec()
+			"new test topic 1".as_bytes().to_vec(),
+			vec![]
 		)
 		.is_ok());
 		assert!(
@@ -49,12 +55,14 @@ fn test_voting_end_to_end() {
 		// Now we've confirmed that the new topic is in the next batch. Let's create new topics
 		assert!(QuadVoting::submit_topic(
 			Origin::signed(1),
-			"new test topic 2".as_bytes().to_vec()
+			"new test topic 2".as_bytes().to_vec(),
+			vec![]
 		)
 		.is_ok());
 		assert!(QuadVoting::submit_topic(
 			Origin::signed(1),
-			"new test topic 3".as_bytes().to_vec()
+			"new test topic 3".as_bytes().to_vec(),
+			vec![]
 		)
 		.is_ok());
 
@@ -70,12 +78,12 @@ fn test_voting_end_to_end() {
 		assert_eq!(current_topics.len(), 3);
 
 		// Vote for item 1 and 3
-		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), current_topics[0]));
-		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), current_topics[2]));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), current_topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), current_topics[2], None));
 
 		// Use a different user to vote only item 2
-		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), current_topics[1]));
-		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), current_topics[2]));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), current_topics[1], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), current_topics[2], None));
 
 		// Get votes for blcok
 		let votes = QuadVoting::get_votes(System::block_number()).expect("should have votes");
@@ -89,3 +97,572 @@ fn test_voting_end_to_end() {
 		assert_eq!(current_topics[2], winner);
 	})
 }
+
+/// Submit `count` topics and move on to the next era, so that they can be voted for.
+fn start_era_with_topics(count: u8) -> Vec<H256> {
+	for i in 0..count {
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(1), vec![i], vec![]));
+	}
+	run_to_block(20);
+	QuadVoting::on_initialize(System::block_number());
+	QuadVoting::get_current_topics().expect("should have current topics")
+}
+
+#[test]
+fn only_topics_of_the_current_era_can_be_voted_for() {
+	new_test_ext().execute_with(|| {
+		let topics = start_era_with_topics(1);
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(2), vec![42], vec![]));
+		let queued = QuadVoting::get_next_topics().expect("should have next topics");
+		assert_noop!(
+			QuadVoting::vote_topic(Origin::signed(1), queued[0], None),
+			Error::<Test>::InvalidTopicHash
+		);
+
+		// Once its era is over, a topic can't be voted for anymore either.
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+		assert_noop!(
+			QuadVoting::vote_topic(Origin::signed(1), topics[0], None),
+			Error::<Test>::InvalidTopicHash
+		);
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), queued[0], None));
+	});
+}
+
+#[test]
+fn voters_over_the_era_cap_are_waitlisted() {
+	new_test_ext().execute_with(|| {
+		let topics = start_era_with_topics(1);
+
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[0], None));
+		assert_eq!(QuadVoting::get_voter_count(20), 2);
+
+		// The era is full, so voter 3 is queued without being charged.
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(3), topics[0], None));
+		System::assert_last_event(Event::QuadVoting(crate::Event::VoterWaitlisted {
+			who: 3,
+			era: 20,
+		}));
+		assert_eq!(QuadVoting::get_waitlist(20).into_inner(), vec![3]);
+		assert_eq!(Balances::reserved_balance(3), 0);
+		assert_eq!(QuadVoting::get_votes(20).expect("should have votes").len(), 2);
+
+		assert_noop!(
+			QuadVoting::vote_topic(Origin::signed(3), topics[0], None),
+			Error::<Test>::StillWaitlisted
+		);
+
+		// The waitlist only holds a single account in the mock runtime.
+		assert_noop!(
+			QuadVoting::vote_topic(Origin::signed(4), topics[0], None),
+			Error::<Test>::WaitlistFull
+		);
+	});
+}
+
+#[test]
+fn withdrawing_votes_admits_the_next_waitlisted_voter() {
+	new_test_ext().execute_with(|| {
+		let topics = start_era_with_topics(1);
+
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(3), topics[0], None));
+		assert!(Balances::reserved_balance(1) > 10);
+
+		assert_ok!(QuadVoting::withdraw_votes(Origin::signed(1)));
+		System::assert_last_event(Event::QuadVoting(crate::Event::VoterAdmitted {
+			who: 3,
+			era: 20,
+		}));
+		// Only the topic deposit is left reserved.
+		assert_eq!(Balances::reserved_balance(1), 10);
+		assert!(!QuadVoting::is_era_voter(20, 1));
+		assert!(QuadVoting::get_waitlist(20).is_empty());
+
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(3), topics[0], None));
+		assert_eq!(QuadVoting::get_votes(20).expect("should have votes").len(), 2);
+
+		assert_noop!(QuadVoting::withdraw_votes(Origin::signed(1)), Error::<Test>::NotAVoter);
+	});
+}
+
+#[test]
+fn voters_of_the_winning_topic_get_a_rebate() {
+	new_test_ext().execute_with(|| {
+		let topics = start_era_with_topics(2);
+
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[1], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[1], None));
+		// Two topic deposits of 10 and a single vote of 10.
+		assert_eq!(Balances::reserved_balance(1), 30);
+		// Two votes on the same topic, of 10 and 40.
+		assert_eq!(Balances::reserved_balance(2), 50);
+
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+
+		assert_eq!(QuadVoting::get_winners(20), Some(topics[1]));
+		// WinnerRebate is 50% in the mock runtime.
+		System::assert_has_event(Event::QuadVoting(crate::Event::FeeRebated {
+			who: 2,
+			era: 20,
+			amount: 25,
+		}));
+		assert_eq!(Balances::reserved_balance(2), 25);
+		assert_eq!(Balances::reserved_balance(1), 30);
+	});
+}
+
+#[test]
+fn cancel_era_refunds_everything_and_requeues_topics() {
+	new_test_ext().execute_with(|| {
+		let topics = start_era_with_topics(2);
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(3), vec![42], vec![]));
+
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[1], None));
+
+		assert_noop!(
+			QuadVoting::cancel_era(Origin::signed(1), 2, 2),
+			sp_runtime::DispatchError::BadOrigin
+		);
+		// The counts given must cover the two voters and the two topics of the era.
+		assert_noop!(QuadVoting::cancel_era(Origin::root(), 1, 2), Error::<Test>::InvalidWitness);
+		assert_noop!(QuadVoting::cancel_era(Origin::root(), 2, 1), Error::<Test>::InvalidWitness);
+		assert_ok!(QuadVoting::cancel_era(Origin::root(), 2, 2));
+		System::assert_last_event(Event::QuadVoting(crate::Event::EraCancelled { era: 20 }));
+
+		// Deposits of the cancelled topics and all vote fees are back, only the deposit of the
+		// topic submitted for the next era is still reserved.
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::reserved_balance(2), 0);
+		assert_eq!(Balances::reserved_balance(3), 10);
+
+		assert!(QuadVoting::get_current_topics().is_none());
+		let next_topics = QuadVoting::get_next_topics().expect("should have next topics");
+		assert_eq!(next_topics.len(), 3);
+		assert_eq!(next_topics[..2], topics[..]);
+
+		assert_noop!(
+			QuadVoting::vote_topic(Origin::signed(1), topics[0], None),
+			Error::<Test>::EraIsCancelled
+		);
+
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+		assert!(QuadVoting::get_winners(20).is_none());
+		assert_eq!(QuadVoting::get_current_topics().map(|t| t.len()), Some(3));
+	});
+}
+
+#[test]
+fn fee_exempt_accounts_pay_nothing_but_are_still_capped() {
+	new_test_ext().execute_with(|| {
+		// Account 5 only holds 2 units, which is not enough for a topic deposit or a vote fee.
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(5), vec![0], vec![]));
+		run_to_block(20);
+		QuadVoting::on_initialize(System::block_number());
+		let topics = QuadVoting::get_current_topics().expect("should have current topics");
+
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(5), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(5), topics[0], None));
+		assert_eq!(Balances::reserved_balance(5), 0);
+		assert!(QuadVoting::get_vote_receipts(20, 5).iter().all(|receipt| receipt.fee == 0));
+
+		// The era cap still applies to exempt accounts.
+		assert_ok!(QuadVoting::withdraw_votes(Origin::signed(5)));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(5), topics[0], None));
+		assert_eq!(QuadVoting::get_waitlist(20).into_inner(), vec![5]);
+	});
+}
+
+#[cfg(feature = "offchain-notify")]
+#[test]
+fn era_summary_describes_the_era_result() {
+	new_test_ext().execute_with(|| {
+		let topics = start_era_with_topics(1);
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+
+		let summary = crate::notify::era_summary::<Test>(20);
+		// The `Debug` output of a hash is its full 0x prefixed hex encoding.
+		let expected = format!(
+			"{{\"era\":20,\"cancelled\":false,\"winner\":\"{:?}\",\"winning_option\":null,\"votes\":1}}",
+			topics[0]
+		);
+		assert_eq!(String::from_utf8(summary).unwrap(), expected);
+	});
+}
+
+#[cfg(feature = "offchain-notify")]
+fn expected_notification(era: u64) -> sp_core::offchain::testing::PendingRequest {
+	sp_core::offchain::testing::PendingRequest {
+		method: "POST".into(),
+		uri: "http://localhost/hook".into(),
+		headers: vec![("Content-Type".into(), "application/json".into())],
+		body: crate::notify::era_summary::<Test>(era),
+		response: Some(vec![]),
+		sent: true,
+		..Default::default()
+	}
+}
+
+#[cfg(feature = "offchain-notify")]
+#[test]
+fn notifications_catch_up_on_missed_and_failed_eras() {
+	use frame_support::sp_runtime::offchain::storage::StorageValueRef;
+	use sp_core::offchain::{testing::TestOffchainExt, OffchainDbExt, OffchainWorkerExt};
+
+	let (offchain, state) = TestOffchainExt::new();
+	let mut ext = new_test_ext();
+	ext.register_extension(OffchainDbExt::new(offchain.clone()));
+	ext.register_extension(OffchainWorkerExt::new(offchain));
+	ext.execute_with(|| {
+		let webhooks = StorageValueRef::persistent(crate::notify::WEBHOOKS_KEY);
+		let topics = start_era_with_topics(1);
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+
+		// An endpoint which can't be reached doesn't count as a delivery, so the era is kept.
+		webhooks.set(&vec![vec![0xffu8]]);
+		crate::notify::notify_era_result::<Test>(45);
+		assert!(state.read().requests.is_empty());
+
+		// The worker doesn't run on the first block of the next era, which is caught up on later
+		// along with the failed one.
+		webhooks.set(&vec![b"http://localhost/hook".to_vec()]);
+		run_to_block(60);
+		QuadVoting::on_initialize(System::block_number());
+		state.write().expect_request(expected_notification(20));
+		state.write().expect_request(expected_notification(40));
+		crate::notify::notify_era_result::<Test>(65);
+		assert_eq!(state.read().requests.len(), 2);
+	});
+}
+
+#[cfg(feature = "offchain-notify")]
+#[test]
+fn each_era_is_only_notified_once() {
+	use frame_support::sp_runtime::offchain::storage::StorageValueRef;
+	use sp_core::offchain::{testing::TestOffchainExt, OffchainDbExt, OffchainWorkerExt};
+
+	let (offchain, state) = TestOffchainExt::new();
+	let mut ext = new_test_ext();
+	ext.register_extension(OffchainDbExt::new(offchain.clone()));
+	ext.register_extension(OffchainWorkerExt::new(offchain));
+	ext.execute_with(|| {
+		StorageValueRef::persistent(crate::notify::WEBHOOKS_KEY)
+			.set(&vec![b"http://localhost/hook".to_vec()]);
+		start_era_with_topics(1);
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+
+		state.write().expect_request(expected_notification(20));
+		crate::notify::notify_era_result::<Test>(40);
+		// Later blocks of the same era, or a fork of the first one, don't announce it again.
+		crate::notify::notify_era_result::<Test>(41);
+		crate::notify::notify_era_result::<Test>(40);
+		assert_eq!(state.read().requests.len(), 1);
+	});
+}
+
+#[test]
+fn metrics_keep_running_totals() {
+	new_test_ext().execute_with(|| {
+		let topics = start_era_with_topics(2);
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[1], None));
+		// Waitlisted voters don't count as a cast vote.
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(3), topics[1], None));
+		let fees = Balances::reserved_balance(1) - 20 + Balances::reserved_balance(2);
+
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+
+		assert_eq!(QuadVoting::total_topics_submitted(), 2);
+		assert_eq!(QuadVoting::total_votes_cast(), 2);
+		assert_eq!(QuadVoting::total_fees_collected(), fees);
+		// Both the era starting at block 0 and the one at block 20 have been finalized.
+		assert_eq!(QuadVoting::total_eras_finalized(), 2);
+		assert_eq!(QuadVoting::metrics().votes_cast, 2);
+	});
+}
+
+#[test]
+fn votes_can_be_proven_against_the_era_vote_root() {
+	new_test_ext().execute_with(|| {
+		let topics = start_era_with_topics(3);
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[2], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[1], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[1], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[2], None));
+
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+		assert!(QuadVoting::get_vote_root(20).is_some());
+
+		let records = QuadVoting::vote_records(20);
+		assert_eq!(records.len(), 4);
+		for (who, topic_hash, count) in records {
+			let (proven_count, proof) =
+				QuadVoting::vote_proof(20, &who, &topic_hash).expect("should have a proof");
+			assert_eq!(proven_count, count);
+			assert!(QuadVoting::verify_vote(20, &who, &topic_hash, count, &proof));
+			assert!(!QuadVoting::verify_vote(20, &who, &topic_hash, count + 1, &proof));
+		}
+		assert!(QuadVoting::vote_proof(20, &3, &topics[0]).is_none());
+	});
+}
+
+#[test]
+fn multi_option_topics_record_the_winning_option() {
+	new_test_ext().execute_with(|| {
+		let budgets = vec![b"10k".to_vec(), b"20k".to_vec(), b"50k".to_vec()];
+		assert_noop!(
+			QuadVoting::submit_topic(Origin::signed(1), b"budget".to_vec(), vec![vec![0]; 5]),
+			Error::<Test>::TooManyOptions
+		);
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(1), b"budget".to_vec(), budgets));
+		run_to_block(20);
+		QuadVoting::on_initialize(System::block_number());
+		let topics = QuadVoting::get_current_topics().expect("should have current topics");
+
+		assert_noop!(
+			QuadVoting::vote_topic(Origin::signed(1), topics[0], None),
+			Error::<Test>::InvalidOption
+		);
+		assert_noop!(
+			QuadVoting::vote_topic(Origin::signed(1), topics[0], Some(3)),
+			Error::<Test>::InvalidOption
+		);
+
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], Some(2)));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[0], Some(1)));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[0], Some(2)));
+		assert_eq!(QuadVoting::get_option_tallies(20, topics[0]), vec![0, 1, 2]);
+
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+		assert_eq!(QuadVoting::get_winners(20), Some(topics[0]));
+		assert_eq!(QuadVoting::get_winning_option(20), Some(2));
+	});
+}
+
+#[test]
+fn budget_allocation_mode_splits_the_budget_by_votes() {
+	new_test_ext().execute_with(|| {
+		let _ = Balances::deposit_creating(&QuadVoting::account_id(), 500);
+		assert_ok!(QuadVoting::set_settlement_mode(
+			Origin::root(),
+			SettlementMode::BudgetAllocation
+		));
+
+		// Topics from three different providers.
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(2), vec![0], vec![]));
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(3), vec![1], vec![]));
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(4), vec![2], vec![]));
+		run_to_block(20);
+		QuadVoting::on_initialize(System::block_number());
+		let topics = QuadVoting::get_current_topics().expect("should have current topics");
+
+		// 6 votes for the first topic, 3 for the second and only 1 for the third one, which is
+		// below the 20% threshold.
+		for _ in 0..4 {
+			assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		}
+		for _ in 0..2 {
+			assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[1], None));
+			assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[0], None));
+		}
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[1], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[2], None));
+		// 10 + 40 + 90 + 160 for the first topic and 10 + 40 for the second one.
+		assert_eq!(Balances::reserved_balance(1), 350);
+		// The topic deposit, 10 + 40 for the first topic and 10 for each of the others.
+		assert_eq!(Balances::reserved_balance(2), 80);
+
+		let free_before: Vec<u64> = (2..=4).map(|who| Balances::free_balance(who)).collect();
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+
+		// The budget of 90 is split 6 to 3 between the first two topics.
+		let free_after: Vec<u64> = (2..=4).map(|who| Balances::free_balance(who)).collect();
+		assert_eq!(free_after[0] - free_before[0], 60);
+		assert_eq!(free_after[1] - free_before[1], 30);
+		assert_eq!(free_after[2], free_before[2]);
+		assert_eq!(Balances::free_balance(QuadVoting::account_id()), 410);
+		assert!(QuadVoting::get_winners(20).is_none());
+	});
+}
+
+#[test]
+fn balance_weighted_eras_count_votes_by_snapshotted_balance() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(QuadVoting::set_balance_weighting(Origin::root(), true));
+		let topics = start_era_with_topics(2);
+		assert_eq!(QuadVoting::get_snapshot_block(20), Some(20));
+
+		// Account 4 only held 100 when the era started, the funds it gets afterwards don't count.
+		assert_ok!(Balances::transfer(Origin::signed(3), 4, 900));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(4), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(4), topics[0], None));
+		assert_eq!(QuadVoting::balance_at_snapshot(20, &4), Some(100));
+
+		// Account 1 held 980 once its two topic deposits were reserved, and selling after voting
+		// doesn't take its vote weight away.
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[1], None));
+		assert_ok!(Balances::transfer(Origin::signed(1), 3, 500));
+		assert_eq!(QuadVoting::balance_at_snapshot(20, &1), Some(980));
+
+		// Accounts whose balance didn't change are at their current balance.
+		assert_eq!(QuadVoting::balance_at_snapshot(20, &2), Some(1000));
+
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+		assert_eq!(QuadVoting::get_winners(20), Some(topics[1]));
+		// The next era is weighted by the balances at its own first block.
+		assert_eq!(QuadVoting::get_snapshot_block(40), Some(40));
+		assert_eq!(QuadVoting::balance_at_snapshot(40, &1), Some(Balances::free_balance(1)));
+	});
+}
+
+#[test]
+fn balance_weighted_eras_weigh_the_options_too() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(QuadVoting::set_balance_weighting(Origin::root(), true));
+		let options = vec![b"yes".to_vec(), b"no".to_vec()];
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(1), b"proposal".to_vec(), options));
+		run_to_block(20);
+		QuadVoting::on_initialize(System::block_number());
+		let topics = QuadVoting::get_current_topics().expect("should have current topics");
+
+		// Account 4 casts more votes, but account 1 holds far more balance.
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(4), topics[0], Some(0)));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(4), topics[0], Some(0)));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], Some(1)));
+		assert_eq!(QuadVoting::get_option_tallies(20, topics[0]), vec![2, 1]);
+
+		run_to_block(40);
+		QuadVoting::on_initialize(System::block_number());
+		assert_eq!(QuadVoting::get_winning_option(20), Some(1));
+	});
+}
+
+#[test]
+fn voters_of_weighted_eras_can_still_reserve_funds() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(QuadVoting::set_balance_weighting(Origin::root(), true));
+		let topics = start_era_with_topics(2);
+
+		// Further vote fees and topic deposits are reserved as usual.
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[0], None));
+		assert_ok!(QuadVoting::vote_topic(Origin::signed(2), topics[0], None));
+		assert_ok!(QuadVoting::submit_topic(Origin::signed(2), vec![42], vec![]));
+		assert_eq!(Balances::reserved_balance(2), 10 + 40 + 10);
+
+		// The vote weight is still the balance from before any of them.
+		assert_eq!(QuadVoting::balance_at_snapshot(20, &2), Some(1000));
+	});
+}
+
+#[test]
+fn calls_check_the_configured_origins() {
+	new_test_ext().execute_with(|| {
+		// The mock runtime keeps the default signed / signed / root origins.
+		assert_noop!(
+			QuadVoting::submit_topic(Origin::root(), vec![0], vec![]),
+			sp_runtime::DispatchError::BadOrigin
+		);
+		let topics = start_era_with_topics(1);
+		assert_noop!(
+			QuadVoting::vote_topic(Origin::none(), topics[0], None),
+			sp_runtime::DispatchError::BadOrigin
+		);
+		assert_noop!(
+			QuadVoting::set_balance_weighting(Origin::signed(1), true),
+			sp_runtime::DispatchError::BadOrigin
+		);
+		assert_ok!(QuadVoting::set_balance_weighting(Origin::root(), true));
+	});
+}
+
+#[test]
+fn change_vote_moves_votes_and_settles_the_fee_difference() {
+	new_test_ext().execute_with(|| {
+		let topics = start_era_with_topics(2);
+		for _ in 0..3 {
+			assert_ok!(QuadVoting::vote_topic(Origin::signed(1), topics[0], None));
+		}
+		// Two topic deposits of 10, then 10 + 40 + 90 for the votes.
+		assert_eq!(Balances::reserved_balance(1), 160);
+		assert_eq!(QuadVoting::total_fees_collected(), 140);
+
+		assert_noop!(
+			QuadVoting::change_vote(Origin::signed(1), topics[0], topics[0], 1, None),
+			Error::<Test>::SameTopic
+		);
+		assert_noop!(
+			QuadVoting::change_vote(Origin::signed(1), topics[1], topics[0], 1, None),
+			Error::<Test>::NoVotesToMove
+		);
+
+		assert_ok!(QuadVoting::change_vote(Origin::signed(1), topics[0], topics[1], 2, None));
+		System::assert_last_event(Event::QuadVoting(crate::Event::VotesChanged {
+			who: 1,
+			from_topic: topics[0],
+			to_topic: topics[1],
+			count: 2,
+		}));
+
+		let votes = QuadVoting::get_votes(20).expect("should have votes");
+		assert_eq!(votes.iter().filter(|(topic, _)| *topic == topics[0]).count(), 1);
+		assert_eq!(votes.iter().filter(|(topic, _)| *topic == topics[1]).count(), 2);
+
+		// The two latest votes, of 40 and 90, are refunded, and priced again at 10 and 40 on the
+		// other topic.
+		let receipts = QuadVoting::get_vote_receipts(20, 1);
+		assert_eq!(receipts.iter().filter(|receipt| receipt.topic == topics[1]).count(), 2);
+		assert_eq!(Balances::reserved_balance(1), 80);
+		// Moving to cheaper votes doesn't collect anything new.
+		assert_eq!(QuadVoting::total_fees_collected(), 140);
+
+		// Asking to move more votes than there are only moves the ones left.
+		assert_ok!(QuadVoting::change_vote(Origin::signed(1), topics[1], topics[0], 5, None));
+		let votes = QuadVoting::get_votes(20).expect("should have votes");
+		assert!(votes.iter().all(|(topic, _)| *topic == topics[0]));
+		assert_eq!(votes.len(), 3);
+		// Moving back onto the topic which still has a vote costs 40 + 90 again, of which only
+		// the 80 not covered by the refund of 10 + 40 is newly collected.
+		assert_eq!(Balances::reserved_balance(1), 160);
+		assert_eq!(QuadVoting::total_fees_collected(), 220);
+	});
+}
+
+#[test]
+fn migration_to_v1_adds_empty_options_to_topics() {
+	new_test_ext().execute_with(|| {
+		StorageVersion::new(0).put::<QuadVoting>();
+		// A topic as encoded before options were introduced: data, provider, deposit and since.
+		let topic_hash = H256::repeat_byte(1);
+		let key = crate::pallet::Topics::<Test>::hashed_key_for(topic_hash);
+		storage::unhashed::put_raw(&key, &(vec![7u8], 1u64, 10u64, 20u64).encode());
+
+		crate::migrations::v1::migrate::<Test>();
+
+		let topic =
+			QuadVoting::get_topic_preimage(topic_hash).expect("should have migrated the topic");
+		assert_eq!(topic.data, vec![7]);
+		assert_eq!(topic.provider, 1);
+		assert!(topic.options.is_empty());
+		assert_eq!(QuadVoting::on_chain_storage_version(), 1);
+	});
+}
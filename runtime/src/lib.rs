@@ -6,8 +6,8 @@
 #[cfg(feature = "std")]
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
-use frame_support::traits::ConstU16;
-use frame_system::EnsureRoot;
+use frame_support::{traits::ConstU16, PalletId};
+use frame_system::{EnsureRoot, EnsureSigned};
 use pallet_grandpa::{
 	fg_primitives, AuthorityId as GrandpaId, AuthorityList as GrandpaAuthorityList,
 };
@@ -30,7 +30,8 @@ use sp_version::RuntimeVersion;
 pub use frame_support::{
 	construct_runtime, parameter_types,
 	traits::{
-		ConstU128, ConstU32, ConstU64, ConstU8, KeyOwnerProofSystem, Randomness, StorageInfo,
+		ConstU128, ConstU32, ConstU64, ConstU8, KeyOwnerProofSystem, Nothing, Randomness,
+		StorageInfo,
 	},
 	weights::{
 		constants::{BlockExecutionWeight, ExtrinsicBaseWeight, RocksDbWeight, WEIGHT_PER_SECOND},
@@ -45,7 +46,7 @@ pub use pallet_timestamp::Call as TimestampCall;
 use pallet_transaction_payment::CurrencyAdapter;
 #[cfg(any(feature = "std", test))]
 pub use sp_runtime::BuildStorage;
-pub use sp_runtime::{Perbill, Permill};
+pub use sp_runtime::{Perbill, Percent, Permill};
 
 /// Import the template pallet.
 pub use pallet_quadvoting;
@@ -107,10 +108,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	//   `spec_version`, and `authoring_version` are the same between Wasm and native.
 	// This value is set to 100 to notify Polkadot-JS App (https://polkadot.js.org/apps) to use
 	//   the compatible custom types.
-	spec_version: 100,
+	spec_version: 101,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
-	transaction_version: 1,
+	transaction_version: 2,
 	state_version: 1,
 };
 
@@ -250,7 +251,8 @@ impl pallet_balances::Config for Runtime {
 	type Event = Event;
 	type DustRemoval = ();
 	type ExistentialDeposit = ConstU128<500>;
-	type AccountStore = System;
+	// Records balances for the snapshots of balance weighted eras.
+	type AccountStore = pallet_quadvoting::snapshot::SnapshotAccountStore<Runtime, System>;
 	type WeightInfo = pallet_balances::weights::SubstrateWeight<Runtime>;
 }
 
@@ -285,6 +287,12 @@ impl pallet_identity::Config for Runtime {
 	type WeightInfo = ();
 }
 
+parameter_types! {
+	pub const WinnerRebate: Percent = Percent::from_percent(20);
+	pub const QuadVotingPalletId: PalletId = PalletId(*b"py/qdvot");
+	pub const AllocationThreshold: Percent = Percent::from_percent(5);
+}
+
 /// Configure the pallet-template in pallets/template.
 impl pallet_quadvoting::Config for Runtime {
 	type Event = Event;
@@ -292,6 +300,17 @@ impl pallet_quadvoting::Config for Runtime {
 	type MaxVotes = ConstU16<10>;
 	type Currency = Balances;
 	type OneBlock = ConstU32<1>;
+	type MaxVotersPerEra = ();
+	type MaxWaitlist = ConstU32<256>;
+	type WinnerRebate = WinnerRebate;
+	type MaxTopicOptions = ConstU32<8>;
+	type PalletId = QuadVotingPalletId;
+	type EraBudget = ConstU128<1_000_000_000_000>;
+	type AllocationThreshold = AllocationThreshold;
+	type FeeExempt = Nothing;
+	type SubmitOrigin = EnsureSigned<AccountId>;
+	type VoteOrigin = EnsureSigned<AccountId>;
+	type AdminOrigin = EnsureRoot<AccountId>;
 }
 
 // Create the runtime by composing the FRAME pallets that were previously configured.